@@ -1,12 +1,22 @@
 use super::semaphore::ShrinkableSemaphore;
+use once_cell::sync::Lazy;
 use std::cmp::max;
+use std::collections::HashMap;
 use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::OwnedSemaphorePermit;
 
 const EWMA_ALPHA: f64 = 0.5;
 const THRESHOLD_RATIO: f64 = 0.01;
+// The minimum RTT estimate follows any lower sample at once but decays
+// upward very slowly, so that it keeps tracking the true no-load latency
+// without being dragged up by a single high sample, while still being
+// able to re-learn after a deploy changes it.
+const MIN_RTT_DECAY: f64 = 0.05;
+const GRADIENT_MIN: f64 = 0.5;
+const GRADIENT_MAX: f64 = 1.0;
 
 #[derive(Clone, Copy, Debug, Default)]
 struct EWMA {
@@ -54,19 +64,246 @@ enum ResponseType {
     Other,
 }
 
+/// Selects the algorithm used by [`Controller`] to grow and shrink the
+/// concurrency limit in response to observed latency and back pressure.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ConcurrencyAlgorithm {
+    /// The original additive-increase/multiplicative-decrease scheme: grow
+    /// the limit by one permit on a fast response, halve it on back
+    /// pressure or an RTT significantly above the running average.
+    Aimd,
+    /// A TCP-Vegas style gradient scheme that compares each RTT sample to
+    /// a long-lived estimate of the no-load RTT and grows or shrinks the
+    /// limit proportionally, converging faster and oscillating less under
+    /// noisy latency than AIMD.
+    Gradient,
+}
+
+impl Default for ConcurrencyAlgorithm {
+    fn default() -> Self {
+        Self::Aimd
+    }
+}
+
 pub(crate) trait IsBackPressure {
     fn is_back_pressure(&self) -> bool;
 }
 
+/// A concurrency budget shared across multiple [`Controller`]s, e.g. ones
+/// belonging to sinks that all talk to the same downstream or share a
+/// single egress path. Each participating controller draws a permit from
+/// the shared budget before drawing one from its own limit, so the group
+/// divides one auto-tuned pool instead of each independently growing into
+/// the same bottleneck.
+#[derive(Debug)]
+pub(crate) struct SharedConcurrency {
+    semaphore: Arc<ShrinkableSemaphore>,
+    max: usize,
+    inner: Mutex<SharedInner>,
+}
+
+#[derive(Debug)]
+struct SharedInner {
+    current: usize,
+    // The last-reported health (as passed to `report`) of each
+    // participating controller, keyed by its participant id. The budget
+    // only grows once every known participant agrees it's healthy, so one
+    // healthy sink can't keep growing the shared pool out from under a
+    // struggling one.
+    health: HashMap<u64, bool>,
+}
+
+static NEXT_PARTICIPANT_ID: AtomicU64 = AtomicU64::new(0);
+
+impl SharedConcurrency {
+    fn new(max: usize, initial: usize) -> Arc<Self> {
+        Arc::new(Self {
+            semaphore: Arc::new(ShrinkableSemaphore::new(initial)),
+            max,
+            inner: Mutex::new(SharedInner {
+                current: initial,
+                health: HashMap::new(),
+            }),
+        })
+    }
+
+    fn acquire(&self) -> impl Future<Output = OwnedSemaphorePermit> + Send + 'static {
+        self.semaphore.clone().acquire()
+    }
+
+    /// Removes a participant's last-reported health, e.g. when its
+    /// controller is torn down. Without this, a participant that reported
+    /// unhealthy and then disappeared (a sink reconfigured or reloaded)
+    /// would leave a stale `false` in `health` that no other participant
+    /// can ever outvote, permanently blocking growth.
+    fn deregister(&self, participant: u64) {
+        self.inner
+            .lock()
+            .expect("SharedConcurrency mutex is poisoned")
+            .health
+            .remove(&participant);
+    }
+
+    /// Called by a participating controller once per settled adjustment
+    /// window (the same cadence the controller uses for its own local
+    /// adjustment), so the shared pool is tuned from the aggregate back
+    /// pressure signal across all participants rather than any single one.
+    /// An unhealthy report from any one participant shrinks the pool
+    /// immediately; growing it back up requires every known participant to
+    /// currently report healthy.
+    fn report(&self, participant: u64, healthy: bool) {
+        let mut inner = self
+            .inner
+            .lock()
+            .expect("SharedConcurrency mutex is poisoned");
+        inner.health.insert(participant, healthy);
+        if !healthy {
+            if inner.current > 1 {
+                let to_forget = inner.current / 2;
+                self.semaphore.forget_permits(to_forget);
+                inner.current -= to_forget;
+            }
+        } else if inner.current < self.max && inner.health.values().all(|healthy| *healthy) {
+            self.semaphore.add_permits(1);
+            inner.current += 1;
+        }
+    }
+}
+
+static SHARED_CONCURRENCY_BUDGETS: Lazy<Mutex<HashMap<String, Arc<SharedConcurrency>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Looks up the named shared concurrency budget, creating it with the
+/// given bounds the first time it's referenced. Sink configs that want to
+/// cooperatively divide a single budget reference it by the same name.
+pub(crate) fn shared_concurrency_budget(
+    name: &str,
+    max: usize,
+    initial: usize,
+) -> Arc<SharedConcurrency> {
+    SHARED_CONCURRENCY_BUDGETS
+        .lock()
+        .expect("shared concurrency budget registry mutex is poisoned")
+        .entry(name.to_owned())
+        .or_insert_with(|| SharedConcurrency::new(max, initial))
+        .clone()
+}
+
+/// Lets a response surface explicit rate-limit information reported by the
+/// downstream (`Retry-After`, `X-RateLimit-Remaining`, `X-RateLimit-Reset`
+/// style headers), so the [`Controller`] can treat the advertised quota as
+/// a proactive ceiling instead of only discovering it through a `429`
+/// back pressure response. All methods default to reporting nothing, so
+/// implementing this trait is only necessary for response types that
+/// actually carry this information.
+pub(crate) trait RateLimitFeedback {
+    /// How long the caller was told to wait before retrying.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The number of requests still permitted in the current window.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+}
+
 /// Shared class for `tokio::sync::Semaphore` that manages adjusting the
 /// semaphore size and other associated data.
 #[derive(Debug)]
 pub(super) struct Controller {
     semaphore: Arc<ShrinkableSemaphore>,
     max: usize,
+    algorithm: ConcurrencyAlgorithm,
+    shared: Option<Arc<SharedConcurrency>>,
+    participant_id: u64,
+    waiting: Arc<AtomicUsize>,
+    in_flight: Arc<AtomicUsize>,
     inner: Arc<Mutex<Inner>>,
 }
 
+impl Drop for Controller {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            shared.deregister(self.participant_id);
+        }
+    }
+}
+
+/// A cheap, point-in-time snapshot of [`Controller`] state, intended to be
+/// polled periodically for dashboards and adaptive tuning decisions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ControllerSnapshot {
+    /// The current concurrency limit.
+    pub(crate) limit: usize,
+    /// The configured ceiling on the concurrency limit.
+    pub(crate) max: usize,
+    /// Permits currently checked out and in use.
+    pub(crate) in_flight: usize,
+    /// Acquirers currently waiting for a permit.
+    pub(crate) waiting: usize,
+    /// The smoothed round-trip time, once at least one window has closed.
+    pub(crate) smoothed_rtt: Option<Duration>,
+    /// The smoothed time acquirers have spent blocked in `acquire()`
+    /// waiting for a permit, once at least one has been granted. Distinct
+    /// from `smoothed_rtt` so operators can tell "downstream is slow" from
+    /// "we throttled ourselves".
+    pub(crate) smoothed_wait_time: Option<Duration>,
+    /// How long it has been since the controller last observed back
+    /// pressure, or `None` if it never has.
+    pub(crate) time_since_back_pressure: Option<Duration>,
+}
+
+/// Returned by [`Controller::acquire_bounded`] when it declines to wait for
+/// a permit, either because the queue was already at its configured depth
+/// or because the deadline elapsed first. Lets a caller shed the request
+/// (or route it to a fallback) instead of buffering it indefinitely.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Shed;
+
+/// A permit obtained via [`Controller::acquire`]. Dropping it releases the
+/// local permit and, if this controller participates in a
+/// [`SharedConcurrency`] budget, the shared permit as well.
+#[derive(Debug)]
+pub(super) struct Permit {
+    _shared: Option<OwnedSemaphorePermit>,
+    _local: OwnedSemaphorePermit,
+    in_flight: Arc<AtomicUsize>,
+    /// How long this acquirer spent blocked in `acquire()` waiting for the
+    /// permit to be granted, distinct from the service RTT measured after
+    /// the request is sent, so operators can tell "downstream is slow"
+    /// from "we throttled ourselves".
+    pub(super) wait_time: Duration,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Increments a waiting count for its lifetime and decrements it on drop,
+/// so the count stays accurate even if the future holding the guard is
+/// cancelled while still waiting.
+struct WaitingGuard {
+    waiting: Arc<AtomicUsize>,
+}
+
+impl WaitingGuard {
+    fn new(waiting: &Arc<AtomicUsize>) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self {
+            waiting: Arc::clone(waiting),
+        }
+    }
+}
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 #[derive(Debug)]
 struct Inner {
     current: usize,
@@ -74,66 +311,205 @@ struct Inner {
     next_update: Instant,
     current_rtt: Mean,
     had_back_pressure: bool,
+    // Long-lived estimate of the no-load RTT, used by the gradient
+    // algorithm. Decays slowly so it re-learns after the no-load
+    // latency shifts (e.g. after a deploy) without chasing noise.
+    rtt_min: Option<f64>,
+    last_back_pressure: Option<Instant>,
+    // Smoothed time spent blocked in `acquire()` waiting for a permit,
+    // updated on every permit grant (not gated by the adjustment window).
+    past_wait_time: EWMA,
 }
 
 impl Controller {
-    pub(super) fn new(max: usize, current: usize) -> Self {
+    pub(super) fn new(
+        max: usize,
+        current: usize,
+        algorithm: ConcurrencyAlgorithm,
+        shared: Option<Arc<SharedConcurrency>>,
+    ) -> Self {
         Self {
             semaphore: Arc::new(ShrinkableSemaphore::new(current)),
             max,
+            algorithm,
+            shared,
+            participant_id: NEXT_PARTICIPANT_ID.fetch_add(1, Ordering::Relaxed),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
             inner: Arc::new(Mutex::new(Inner {
                 current: current,
                 past_rtt: Default::default(),
                 next_update: Instant::now(),
                 current_rtt: Default::default(),
                 had_back_pressure: false,
+                rtt_min: None,
+                last_back_pressure: None,
+                past_wait_time: Default::default(),
             })),
         }
     }
 
-    pub(super) fn acquire(&self) -> impl Future<Output = OwnedSemaphorePermit> + Send + 'static {
-        self.semaphore.clone().acquire()
+    /// Takes a cheap snapshot of the controller's current state.
+    pub(super) fn snapshot(&self) -> ControllerSnapshot {
+        let inner = self.inner.lock().expect("Controller mutex is poisoned");
+        let now = Instant::now();
+        ControllerSnapshot {
+            limit: inner.current,
+            max: self.max,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            smoothed_rtt: (inner.past_rtt.average() > 0.0)
+                .then(|| Duration::from_secs_f64(inner.past_rtt.average())),
+            smoothed_wait_time: (inner.past_wait_time.average() > 0.0)
+                .then(|| Duration::from_secs_f64(inner.past_wait_time.average())),
+            time_since_back_pressure: inner
+                .last_back_pressure
+                .map(|at| now.saturating_duration_since(at)),
+        }
+    }
+
+    pub(super) fn acquire(&self) -> impl Future<Output = Permit> + Send + 'static {
+        let shared = self.shared.clone();
+        let local = self.semaphore.clone();
+        let waiting = Arc::clone(&self.waiting);
+        let in_flight = Arc::clone(&self.in_flight);
+        let inner = Arc::clone(&self.inner);
+        async move {
+            // Counts this acquirer as waiting until its permit is granted;
+            // a cancelled or raced acquirer (e.g. dropped out of a
+            // `tokio::select!`) still decrements the count on drop.
+            let waiting_guard = WaitingGuard::new(&waiting);
+            let start = Instant::now();
+            // Draw from the instance-wide budget first (if any), then from
+            // this controller's own limit, so a shared ceiling is always
+            // respected regardless of how many sinks participate in it.
+            let _shared = match shared {
+                Some(shared) => Some(shared.acquire().await),
+                None => None,
+            };
+            let _local = local.acquire().await;
+            drop(waiting_guard);
+            in_flight.fetch_add(1, Ordering::Relaxed);
+            let wait_time = start.elapsed();
+            inner
+                .lock()
+                .expect("Controller mutex is poisoned")
+                .past_wait_time
+                .update(wait_time.as_secs_f64());
+            Permit {
+                _shared,
+                _local,
+                in_flight,
+                wait_time,
+            }
+        }
+    }
+
+    /// The number of acquirers currently waiting for a permit. Exposed so a
+    /// sink can apply backpressure upstream without itself enqueuing a
+    /// would-be request just to probe the depth.
+    pub(super) fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Like [`Controller::acquire`], but bounds how long the caller is
+    /// willing to wait for a permit. Returns `Err(Shed)` immediately if
+    /// `max_queue_depth` acquirers are already waiting, without enqueuing
+    /// this one, and returns `Err(Shed)` if `deadline` passes before a
+    /// permit is granted.
+    pub(super) fn acquire_bounded(
+        &self,
+        max_queue_depth: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> impl Future<Output = Result<Permit, Shed>> + Send + 'static {
+        let waiting = Arc::clone(&self.waiting);
+        let acquire = self.acquire();
+        async move {
+            if let Some(max_queue_depth) = max_queue_depth {
+                if waiting.load(Ordering::Relaxed) >= max_queue_depth {
+                    return Err(Shed);
+                }
+            }
+
+            // `acquire` counts this caller as waiting until the permit is
+            // granted or the wait is abandoned at the deadline.
+            let permit = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    tokio::time::timeout(timeout, acquire).await.ok()
+                }
+                None => Some(acquire.await),
+            };
+
+            permit.ok_or(Shed)
+        }
     }
 
     pub(super) fn adjust_to_response<T, E>(&self, start: Instant, response: &Result<T, E>)
     where
-        E: IsBackPressure,
+        E: IsBackPressure + RateLimitFeedback,
     {
+        // Rate-limit headers are only meaningful on the error path (e.g. a
+        // `429`), so only `E` needs to implement `RateLimitFeedback`; `T`
+        // stays unconstrained, matching every other success-type call site.
+        let (retry_after, remaining) = match response {
+            Ok(_) => (None, None),
+            Err(r) => (r.retry_after(), r.remaining()),
+        };
         let response = match response {
             Ok(_) => ResponseType::Normal,
             Err(r) if r.is_back_pressure() => ResponseType::BackPressure,
             Err(_) => ResponseType::Other,
         };
-        self._adjust_to_response(start, response)
+        self._adjust_to_response(start, response, retry_after, remaining)
     }
 
-    fn _adjust_to_response(&self, start: Instant, response: ResponseType) {
+    fn _adjust_to_response(
+        &self,
+        start: Instant,
+        response: ResponseType,
+        retry_after: Option<Duration>,
+        remaining: Option<usize>,
+    ) {
         let now = Instant::now();
         let rtt = now.saturating_duration_since(start).as_secs_f64();
         let mut inner = self.inner.lock().expect("Controller mutex is poisoned");
         if response == ResponseType::BackPressure {
             inner.had_back_pressure = true;
+            inner.last_back_pressure = Some(now);
         }
 
+        self.apply_rate_limit_feedback(&mut inner, now, retry_after, remaining);
+
+        // The advertised quota, if any, bounds this call's adjustment too,
+        // so a fast RTT closing the window can't immediately grow `current`
+        // back past the ceiling `apply_rate_limit_feedback` just enforced.
+        let ceiling = remaining.map(|remaining| max(remaining, 1));
+
         let rtt = inner.current_rtt.update(rtt);
+        let rtt_min = match inner.rtt_min {
+            None => rtt,
+            Some(rtt_min) if rtt < rtt_min => rtt,
+            Some(rtt_min) => rtt_min + (rtt - rtt_min) * MIN_RTT_DECAY,
+        };
+        inner.rtt_min = Some(rtt_min);
+
         let avg = inner.past_rtt.average();
         if avg > 0.0 && now >= inner.next_update {
-            let threshold = avg * THRESHOLD_RATIO;
-
-            // A back pressure response, either explicit or implicit due
-            // to increasing response times, triggers a decrease in
-            // concurrency.
-            if inner.current > 1 && (inner.had_back_pressure || rtt >= avg + threshold) {
-                // Decrease (multiplicative) the current concurrency
-                let to_forget = inner.current / 2;
-                self.semaphore.forget_permits(to_forget);
-                inner.current -= to_forget;
+            match self.algorithm {
+                ConcurrencyAlgorithm::Aimd => self.adjust_aimd(&mut inner, rtt, avg, ceiling),
+                ConcurrencyAlgorithm::Gradient => {
+                    self.adjust_gradient(&mut inner, rtt, rtt_min, ceiling)
+                }
             }
-            // Normal quick responses triggers an increase in concurrency.
-            else if inner.current < self.max && !inner.had_back_pressure && rtt <= avg {
-                // Increase (additive) the current concurrency
-                self.semaphore.add_permits(1);
-                inner.current += 1;
+
+            // Report to the shared budget on the same per-window cadence as
+            // the local adjustment above, not on every response. The health
+            // reported is whether the response that closes the window was
+            // back pressure; back pressure seen earlier in the window is
+            // not counted.
+            if let Some(shared) = &self.shared {
+                shared.report(self.participant_id, !inner.had_back_pressure);
             }
 
             let new_avg = inner.past_rtt.update(rtt);
@@ -143,4 +519,246 @@ impl Controller {
         inner.had_back_pressure = false;
         inner.current_rtt.reset();
     }
+
+    /// Turns explicit quota information from the downstream into an
+    /// immediate, proactive adjustment of the concurrency limit, rather
+    /// than waiting to discover the same limit through back pressure.
+    fn apply_rate_limit_feedback(
+        &self,
+        inner: &mut Inner,
+        now: Instant,
+        retry_after: Option<Duration>,
+        remaining: Option<usize>,
+    ) {
+        if let Some(remaining) = remaining {
+            // Never advertise (or use) more concurrency than the
+            // downstream told us is left in the current window.
+            let ceiling = max(remaining, 1);
+            if inner.current > ceiling {
+                let to_forget = inner.current - ceiling;
+                self.semaphore.forget_permits(to_forget);
+                inner.current -= to_forget;
+            }
+        }
+
+        if let Some(retry_after) = retry_after {
+            // Hold off on the next adjustment until the advertised reset
+            // time has elapsed, and forget permits down to 1 rather than 0
+            // so at most one request at a time still probes the downstream
+            // during the freeze. Dropping to 0 would otherwise deadlock the
+            // controller forever if no further response ever arrives to
+            // lift it back up.
+            inner.next_update = inner.next_update.max(now + retry_after);
+            if inner.current > 1 {
+                let to_forget = inner.current - 1;
+                self.semaphore.forget_permits(to_forget);
+                inner.current = 1;
+            }
+        }
+    }
+
+    fn adjust_aimd(&self, inner: &mut Inner, rtt: f64, avg: f64, ceiling: Option<usize>) {
+        let threshold = avg * THRESHOLD_RATIO;
+        let max = ceiling.map_or(self.max, |ceiling| ceiling.min(self.max));
+
+        // A back pressure response, either explicit or implicit due
+        // to increasing response times, triggers a decrease in
+        // concurrency.
+        if inner.current > 1 && (inner.had_back_pressure || rtt >= avg + threshold) {
+            // Decrease (multiplicative) the current concurrency
+            let to_forget = inner.current / 2;
+            self.semaphore.forget_permits(to_forget);
+            inner.current -= to_forget;
+        }
+        // Normal quick responses triggers an increase in concurrency.
+        else if inner.current < max && !inner.had_back_pressure && rtt <= avg {
+            // Increase (additive) the current concurrency
+            self.semaphore.add_permits(1);
+            inner.current += 1;
+        }
+    }
+
+    fn adjust_gradient(&self, inner: &mut Inner, rtt: f64, rtt_min: f64, ceiling: Option<usize>) {
+        // Explicit back pressure always forces an immediate multiplicative
+        // drop, the same as AIMD, regardless of what the gradient says.
+        if inner.current > 1 && inner.had_back_pressure {
+            let to_forget = inner.current / 2;
+            self.semaphore.forget_permits(to_forget);
+            inner.current -= to_forget;
+            return;
+        }
+
+        let max = ceiling.map_or(self.max, |ceiling| ceiling.min(self.max));
+        let gradient = (rtt_min / rtt.max(f64::EPSILON)).clamp(GRADIENT_MIN, GRADIENT_MAX);
+        let queue_allowance = (inner.current as f64).sqrt().ceil();
+        let target = (inner.current as f64 * gradient + queue_allowance).clamp(1.0, max as f64);
+        // Smooth the transition so a single noisy sample can't yank the
+        // limit around; this reuses the same EWMA smoothing as `past_rtt`.
+        let smoothed = target * EWMA_ALPHA + inner.current as f64 * (1.0 - EWMA_ALPHA);
+        let new_current = (smoothed.round() as usize).clamp(1, max);
+
+        if new_current > inner.current {
+            self.semaphore.add_permits(new_current - inner.current);
+        } else if new_current < inner.current {
+            self.semaphore.forget_permits(inner.current - new_current);
+        }
+        inner.current = new_current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_controller(algorithm: ConcurrencyAlgorithm, max: usize, current: usize) -> Controller {
+        Controller::new(max, current, algorithm, None)
+    }
+
+    #[test]
+    fn gradient_grows_when_rtt_matches_rtt_min() {
+        let controller = new_controller(ConcurrencyAlgorithm::Gradient, 20, 4);
+        let mut inner = controller.inner.lock().unwrap();
+        controller.adjust_gradient(&mut inner, 0.1, 0.1, None);
+        assert!(inner.current > 4);
+        assert!(inner.current <= controller.max);
+    }
+
+    #[test]
+    fn gradient_shrinks_when_rtt_rises_well_above_rtt_min() {
+        let controller = new_controller(ConcurrencyAlgorithm::Gradient, 200, 100);
+        let mut inner = controller.inner.lock().unwrap();
+        controller.adjust_gradient(&mut inner, 10.0, 0.1, None);
+        assert!(inner.current < 100);
+    }
+
+    #[test]
+    fn gradient_back_pressure_forces_multiplicative_drop_regardless_of_rtt() {
+        let controller = new_controller(ConcurrencyAlgorithm::Gradient, 20, 8);
+        let mut inner = controller.inner.lock().unwrap();
+        inner.had_back_pressure = true;
+        // A gradient that on its own would call for growth (rtt == rtt_min)
+        // must still be overridden by the explicit back pressure signal.
+        controller.adjust_gradient(&mut inner, 0.1, 0.1, None);
+        assert_eq!(inner.current, 4);
+    }
+
+    #[test]
+    fn gradient_grows_window_by_window_toward_max_under_sustained_low_latency() {
+        let controller = new_controller(ConcurrencyAlgorithm::Gradient, 50, 2);
+        for _ in 0..50 {
+            let mut inner = controller.inner.lock().unwrap();
+            controller.adjust_gradient(&mut inner, 0.1, 0.1, None);
+        }
+        let inner = controller.inner.lock().unwrap();
+        assert!(inner.current > 2);
+        assert!(inner.current <= 50);
+    }
+
+    #[test]
+    fn shared_budget_waits_for_all_participants_to_be_healthy_before_growing() {
+        let shared = SharedConcurrency::new(10, 4);
+
+        shared.report(1, false);
+        let after_shrink = shared.inner.lock().unwrap().current;
+        assert!(after_shrink < 4);
+
+        // Participant 2 is healthy, but participant 1's last report was
+        // unhealthy, so the budget must not grow yet.
+        shared.report(2, true);
+        assert_eq!(shared.inner.lock().unwrap().current, after_shrink);
+
+        // Now every known participant agrees it's healthy.
+        shared.report(1, true);
+        assert!(shared.inner.lock().unwrap().current > after_shrink);
+    }
+
+    #[test]
+    fn shared_budget_deregister_clears_a_stale_unhealthy_vote() {
+        let shared = SharedConcurrency::new(10, 4);
+
+        shared.report(1, false);
+        let after_shrink = shared.inner.lock().unwrap().current;
+
+        // Participant 1 goes away (e.g. its sink was reloaded) while its
+        // last report was unhealthy. Without deregistering, that stale
+        // `false` would block growth forever.
+        shared.deregister(1);
+        shared.report(2, true);
+        assert!(shared.inner.lock().unwrap().current > after_shrink);
+    }
+
+    #[tokio::test]
+    async fn acquire_bounded_sheds_when_queue_is_already_full() {
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 4, 4);
+        let result = controller.acquire_bounded(Some(0), None).await;
+        assert_eq!(result.err(), Some(Shed));
+        // The shed path never enqueued the acquirer, so it leaves no trace.
+        assert_eq!(controller.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire_bounded_sheds_past_its_deadline() {
+        // No permits are ever available, so the wait can only end by
+        // hitting the (already-elapsed) deadline.
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 1, 0);
+        let result = controller.acquire_bounded(None, Some(Instant::now())).await;
+        assert_eq!(result.err(), Some(Shed));
+        assert_eq!(controller.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire_bounded_grants_a_permit_when_one_is_available() {
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 1, 1);
+        let result = controller.acquire_bounded(Some(1), None).await;
+        assert!(result.is_ok());
+        assert_eq!(controller.waiting(), 0);
+    }
+
+    #[test]
+    fn rate_limit_remaining_caps_current_and_resists_regrowth_in_the_same_call() {
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 20, 10);
+        {
+            // Seed the window so the AIMD adjustment below actually runs
+            // instead of being skipped by the `avg > 0.0` gate.
+            let mut inner = controller.inner.lock().unwrap();
+            inner.past_rtt.update(1.0);
+            inner.next_update = Instant::now();
+        }
+
+        // A fast response (rtt ~ 0) would normally grow `current` by one on
+        // this window, but the advertised `remaining` must still win.
+        controller._adjust_to_response(Instant::now(), ResponseType::Normal, None, Some(3));
+
+        assert_eq!(controller.inner.lock().unwrap().current, 3);
+    }
+
+    #[test]
+    fn rate_limit_retry_after_forgets_down_to_one_and_delays_next_update() {
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 20, 10);
+        let before = Instant::now();
+
+        controller._adjust_to_response(
+            before,
+            ResponseType::Normal,
+            Some(Duration::from_secs(30)),
+            None,
+        );
+
+        let inner = controller.inner.lock().unwrap();
+        assert_eq!(inner.current, 1);
+        assert!(inner.next_update >= before + Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn snapshot_reflects_in_flight_count_and_smoothed_wait_time_after_acquire() {
+        let controller = new_controller(ConcurrencyAlgorithm::Aimd, 4, 4);
+
+        let permit = controller.acquire().await;
+        let snapshot = controller.snapshot();
+        assert_eq!(snapshot.in_flight, 1);
+        assert!(snapshot.smoothed_wait_time.is_some());
+
+        drop(permit);
+        assert_eq!(controller.snapshot().in_flight, 0);
+    }
 }
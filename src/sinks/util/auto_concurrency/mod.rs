@@ -5,5 +5,8 @@ mod future;
 mod semaphore;
 mod service;
 
-pub(crate) use controller::IsBackPressure;
+pub(crate) use controller::{
+    shared_concurrency_budget, ConcurrencyAlgorithm, ControllerSnapshot, IsBackPressure,
+    RateLimitFeedback, Shed, SharedConcurrency,
+};
 pub(crate) use service::AutoConcurrencyLimit;